@@ -1,14 +1,23 @@
 use std::collections::HashMap;
 use std::ffi::OsString;
 use std::io::{BufReader, Error, Read};
+use std::fs;
 use std::fs::File;
 use std::iter;
 use std::path::{Path, PathBuf};
-use clap::{Parser};
-use ring::digest::{Context, Digest, SHA256};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+use clap::{Parser, ValueEnum};
+use serde::Serialize;
+use ring::digest::{Context, SHA256};
 use data_encoding::HEXUPPER;
 use ansi_term::Colour::Red;
 use unicode_segmentation::UnicodeSegmentation;
+use walkdir::WalkDir;
+use rayon::prelude::*;
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use globset::{Glob, GlobSet, GlobSetBuilder};
 
 /// Simple command line tool to compare the contents of the given folders
 #[derive(Parser, Debug)]
@@ -19,9 +28,27 @@ struct Args {
     #[clap(parse(from_os_str))]
     directories: Vec<PathBuf>,
 
-    /// Optional filter: only regard files with this extension
+    /// Only regard files with this extension (can be given multiple times; a file is included
+    /// if it matches any of them)
     #[clap(long)]
-    extension: Option<OsString>,
+    extension: Vec<OsString>,
+
+    /// Exclude files with this extension (can be given multiple times), applied after `--extension`
+    #[clap(long)]
+    exclude_extension: Vec<OsString>,
+
+    /// Exclude files whose path relative to their directory matches this glob pattern (can be
+    /// given multiple times), e.g. `--exclude-glob "**/target/**"`
+    #[clap(long)]
+    exclude_glob: Vec<String>,
+
+    /// Only regard files that are at least this many bytes large
+    #[clap(long)]
+    min_size: Option<u64>,
+
+    /// Only regard files that are at most this many bytes large
+    #[clap(long)]
+    max_size: Option<u64>,
 
     /// The width of each column in the output ASCII table
     #[clap(long, default_value_t=20)]
@@ -32,48 +59,366 @@ struct Args {
     /// (c) occur more than once in at least one folder
     #[clap(long)]
     diffonly: bool,
+
+    /// How many levels of subdirectories to descend into below each given directory.
+    /// A value of 0 only looks at the files directly inside each directory (the old behavior).
+    #[clap(long, default_value_t=usize::MAX)]
+    max_depth: usize,
+
+    /// Follow symlinks while recursively walking the directories (off by default, since
+    /// following symlinks can lead to infinite loops when a symlink points back up the tree)
+    #[clap(long)]
+    follow_symlinks: bool,
+
+    /// How many threads to hash files with in parallel (defaults to the number of CPU cores)
+    #[clap(long)]
+    jobs: Option<usize>,
+
+    /// Which hash algorithm to use for comparing file contents
+    #[clap(long, value_enum, default_value_t = HashAlgorithm::Sha256)]
+    algorithm: HashAlgorithm,
+
+    /// Path to a file used to cache hashes between runs, keyed by each file's absolute path,
+    /// size and modification time. Only full-content hashes are cached: re-running with the same
+    /// cache on a mostly-unchanged tree skips re-hashing the full content of files that haven't
+    /// changed, but the first block of every file that shares its size with another is still hashed.
+    #[clap(long)]
+    cache: Option<PathBuf>,
+
+    /// Output format for the result
+    #[clap(long, value_enum, default_value_t = OutputFormat::Table)]
+    format: OutputFormat,
+}
+
+/// The output formats that can be selected via `--format`.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+/// The key under which a file's hash is stored in the `--cache` file: its absolute path, size and
+/// modification time (in nanoseconds since the Unix epoch), plus the algorithm it was hashed
+/// with – so that editing, replacing a file, or switching `--algorithm` invalidates the entry.
+type CacheKey = (PathBuf, u64, u128, String);
+
+/// The persistent hash cache, (de)serialized to/from the `--cache` file.
+type HashCache = HashMap<CacheKey, String>;
+
+/// Computes the `--cache` lookup key for a file, based on its current metadata.
+fn cache_key(path: &Path, algorithm: HashAlgorithm) -> Result<CacheKey, Error> {
+    let metadata = path.metadata()?;
+    let mtime_nanos = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    let absolute_path = path.canonicalize()?;
+    Ok((absolute_path, metadata.len(), mtime_nanos, algorithm.name().to_string()))
+}
+
+/// The hash algorithms that can be selected via `--algorithm`.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum HashAlgorithm {
+    Sha256,
+    Blake3,
+    Xxh3,
+    Crc32,
+}
+
+impl HashAlgorithm {
+    /// The name shown in the table header, e.g. "SHA256".
+    fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "SHA256",
+            HashAlgorithm::Blake3 => "BLAKE3",
+            HashAlgorithm::Xxh3 => "XXH3",
+            HashAlgorithm::Crc32 => "CRC32",
+        }
+    }
+
+    /// The length (in hex characters) of a digest produced by this algorithm, used to size the
+    /// table's hash column.
+    fn hex_len(&self) -> usize {
+        match self {
+            HashAlgorithm::Sha256 => 64,
+            HashAlgorithm::Blake3 => 64,
+            HashAlgorithm::Xxh3 => 16,
+            HashAlgorithm::Crc32 => 8,
+        }
+    }
+
+    /// Creates a fresh hasher for this algorithm.
+    fn hasher(&self) -> Box<dyn FileHasher> {
+        match self {
+            HashAlgorithm::Sha256 => Box::new(Sha256Hasher(Context::new(&SHA256))),
+            HashAlgorithm::Blake3 => Box::new(Blake3Hasher(blake3::Hasher::new())),
+            HashAlgorithm::Xxh3 => Box::new(Xxh3Hasher(xxhash_rust::xxh3::Xxh3::new())),
+            HashAlgorithm::Crc32 => Box::new(Crc32Hasher(crc32fast::Hasher::new())),
+        }
+    }
+}
+
+/// A hasher that can be fed chunks of a file and then turned into a hex-encoded digest, so that
+/// `file_hash`/`partial_hash` can stay agnostic of which concrete algorithm is in use.
+trait FileHasher {
+    fn update(&mut self, data: &[u8]);
+    fn finish_hex(self: Box<Self>) -> String;
+}
+
+struct Sha256Hasher(Context);
+impl FileHasher for Sha256Hasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+    fn finish_hex(self: Box<Self>) -> String {
+        HEXUPPER.encode(self.0.finish().as_ref())
+    }
+}
+
+struct Blake3Hasher(blake3::Hasher);
+impl FileHasher for Blake3Hasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+    fn finish_hex(self: Box<Self>) -> String {
+        self.0.finalize().to_hex().to_ascii_uppercase()
+    }
+}
+
+struct Xxh3Hasher(xxhash_rust::xxh3::Xxh3);
+impl FileHasher for Xxh3Hasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+    fn finish_hex(self: Box<Self>) -> String {
+        format!("{:016X}", self.0.digest())
+    }
+}
+
+struct Crc32Hasher(crc32fast::Hasher);
+impl FileHasher for Crc32Hasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+    fn finish_hex(self: Box<Self>) -> String {
+        format!("{:08X}", self.0.finalize())
+    }
 }
 
 fn main() {
     let args = Args::parse();
 
-    // This map maps each hash to all the files in the folders that have that hash/digest:
-    let mut hash_to_files: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    // Compile the `--exclude-glob` patterns (matched against each file's path relative to the
+    // directory it was found in) once, up front:
+    let mut exclude_glob_builder = GlobSetBuilder::new();
+    for pattern in args.exclude_glob.iter() {
+        match Glob::new(pattern) {
+            Ok(glob) => { exclude_glob_builder.add(glob); },
+            Err(error) => eprintln!("{}", Red.paint(format!("Error: Invalid --exclude-glob pattern {}: {}", pattern, error)))
+        }
+    }
+    let exclude_globs: GlobSet = exclude_glob_builder.build().unwrap_or_else(|_| GlobSet::empty());
 
-    // For each directory and for each file in each directory, update `hash_to_files`:
+    // First, walk all directories and collect every file that passes the extension/glob/size
+    // filters, without hashing anything yet, so that hashing itself can be parallelized below:
+    let mut candidates: Vec<PathBuf> = Vec::new();
     for directory in args.directories.iter() {
         if !directory.is_dir() {
             eprintln!("{}", Red.paint(format!("Error: {} is not a directory!", directory.display())));
         } else {
-            match directory.read_dir() {
-                Ok(read_dir) => {
-                    for file in read_dir {
-                        match file {
-                            Ok(file) => {
-                                let file: PathBuf = file.path(); // Turn a DirEntry into a PathBuf.
-                                if args.extension == None || args.extension == file.extension().map(|os_str| os_str.to_os_string()) {
-                                    match file_hash(&file) {
-                                        Ok(hash) => hash_to_files.entry(hash).or_insert(Vec::new()).push(file),
-                                        Err(error) => eprintln!("{}", Red.paint(format!("Error: An error occurred while hashing {}: {}", file.display(), error)))
-                                    }
+            let walker = WalkDir::new(directory)
+                .min_depth(1) // don't yield `directory` itself
+                .max_depth(args.max_depth.saturating_add(1)) // depth 1 = directly inside `directory`
+                .follow_links(args.follow_symlinks);
+            for entry in walker {
+                match entry {
+                    Ok(entry) => {
+                        let file: PathBuf = entry.path().to_path_buf();
+                        if !file.is_file() {
+                            continue; // skip subdirectories (and symlinks to directories)
+                        }
+
+                        // --extension (include if it matches any) / --exclude-extension:
+                        let extension = file.extension();
+                        if !args.extension.is_empty() && !extension.is_some_and(|ext| args.extension.iter().any(|allowed| allowed == ext)) {
+                            continue;
+                        }
+                        if extension.is_some_and(|ext| args.exclude_extension.iter().any(|excluded| excluded == ext)) {
+                            continue;
+                        }
+
+                        // --exclude-glob, matched against the file's path relative to `directory`:
+                        let relative_path = file.strip_prefix(directory).unwrap_or(&file);
+                        if exclude_globs.is_match(relative_path) {
+                            continue;
+                        }
+
+                        // --min-size / --max-size (evaluated here so that excluded files are never even hashed):
+                        match file.metadata() {
+                            Ok(metadata) => {
+                                if args.min_size.is_some_and(|min| metadata.len() < min) {
+                                    continue;
+                                }
+                                if args.max_size.is_some_and(|max| metadata.len() > max) {
+                                    continue;
                                 }
                             },
-                            Err(error) => eprintln!("{}", Red.paint(format!("Error: An IO error occurred while iterating through {}: {}", directory.display(), error)))
+                            Err(error) => {
+                                eprintln!("{}", Red.paint(format!("Error: Could not stat {}: {}", file.display(), error)));
+                                continue;
+                            }
                         }
-                    }
-                },
-                Err(error) => eprintln!("{}", Red.paint(format!("Error: Directory {} could not be read: {}", directory.display(), error)))
+
+                        candidates.push(file);
+                    },
+                    Err(error) => eprintln!("{}", Red.paint(format!("Error: An IO error occurred while walking {}: {}", directory.display(), error)))
+                }
             }
         }
     }
 
-    // Print out the result as an ASCII table:
-    println!(); // Put a newline over and under the ASCII table to make it more readable!
-    println!("#\tSHA256{}\t{}",
-             " ".repeat(64 - "SHA256".len()),
-             args.directories.iter().map(|dir: &PathBuf| fixed_length(dir.file_name().map( |os_str| os_str.to_str()).flatten().unwrap_or("???"), args.colwidth, " ")).collect::<Vec<String>>().join("\t")
+    // Size the rayon thread pool according to `--jobs` (defaults to rayon's own choice, i.e. the number of CPUs):
+    if let Some(jobs) = args.jobs {
+        rayon::ThreadPoolBuilder::new().num_threads(jobs).build_global().expect("Failed to build thread pool");
+    }
+
+    let progress_bar = ProgressBar::new(candidates.len() as u64);
+    progress_bar.set_style(ProgressStyle::with_template("{bar:40} {pos}/{len} files checked ({msg} read)")
+        .unwrap_or(ProgressStyle::default_bar()));
+    let bytes_processed = AtomicU64::new(0);
+
+    // Load the persistent hash cache, if `--cache` was given:
+    let cache: Mutex<HashCache> = Mutex::new(
+        args.cache.as_ref()
+            .filter(|path| path.is_file())
+            .and_then(|path| fs::read(path).ok())
+            .and_then(|bytes| bincode::deserialize(&bytes).ok())
+            .unwrap_or_default()
     );
 
+    // This map maps each (final) hash to all the files that have that hash/digest. It is built up
+    // in three stages below so that most files never need their full content read at all. A
+    // `--cache` hit is only substituted in where that's actually safe (see the comments at each
+    // stage) – pulling a cached file out of the pipeline before its size/partial-hash grouping has
+    // run would make it invisible to the uniqueness checks that decide whether a file needs
+    // hashing at all, and could wrongly report it as distinct from an uncached duplicate:
+    let mut hash_to_files: HashMap<String, Vec<PathBuf>> = HashMap::new();
+
+    // Stage 1: group candidates by file size alone (just a `stat`, no hashing). A file whose size
+    // doesn't occur anywhere else in the candidate set can't be a duplicate of anything, so it's
+    // emitted right away without its full content being read: it is only opened to hash its first
+    // block for its output key, and not even that when it has a `--cache` hit:
+    let mut size_to_files: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    for file in candidates {
+        match file.metadata() {
+            Ok(metadata) => size_to_files.entry(metadata.len()).or_insert(Vec::new()).push(file),
+            Err(error) => eprintln!("{}", Red.paint(format!("Error: Could not stat {}: {}", file.display(), error)))
+        }
+    }
+    let mut unique_by_size: Vec<PathBuf> = Vec::new();
+    let mut needs_partial_hash: Vec<PathBuf> = Vec::new();
+    for (_size, files) in size_to_files {
+        if files.len() == 1 {
+            unique_by_size.extend(files);
+        } else {
+            needs_partial_hash.extend(files);
+        }
+    }
+
+    // A file that's unique by size is provably unique altogether, so nothing else can ever need to
+    // match its key – it's safe to use a `--cache` hit here (skipping `partial_hash` entirely)
+    // instead of a partial hash actually derived from the file's content:
+    let unique_by_size_to_files: HashMap<String, Vec<PathBuf>> = unique_by_size.par_iter()
+        .fold(HashMap::<String, Vec<PathBuf>>::new, |mut map, file| {
+            let cached = cache_key(file, args.algorithm).ok().and_then(|key| cache.lock().unwrap().get(&key).cloned());
+            match cached.map(Ok).unwrap_or_else(|| partial_hash(file, args.algorithm)) {
+                Ok(hash) => map.entry(hash).or_insert(Vec::new()).push(file.clone()),
+                Err(error) => eprintln!("{}", Red.paint(format!("Error: An error occurred while hashing {}: {}", file.display(), error)))
+            }
+            progress_bar.inc(1);
+            map
+        })
+        .reduce(HashMap::new, |mut map1, map2| {
+            for (hash, mut files) in map2 {
+                map1.entry(hash).or_insert(Vec::new()).append(&mut files);
+            }
+            map1
+        });
+    for (hash, files) in unique_by_size_to_files {
+        hash_to_files.entry(hash).or_insert(Vec::new()).extend(files);
+    }
+
+    // Stage 2: for files that share a size, hash only the first block (`PARTIAL_HASH_BLOCK_SIZE`
+    // bytes) of each, in parallel, and group by (size, partial hash). A group of one is already
+    // provably unique and is emitted without ever reading the rest of the file. Unlike stage 1 and
+    // stage 3, a `--cache` hit (a full-content hash) can NOT be substituted for `partial_hash`
+    // here: two files that share a size still need to be grouped by the same kind of hash, or a
+    // cached file and an uncached duplicate of it would be keyed inconsistently and wrongly end up
+    // in different groups:
+    let size_and_partial_hash_to_files: HashMap<(u64, String), Vec<PathBuf>> = needs_partial_hash.par_iter()
+        .fold(HashMap::<(u64, String), Vec<PathBuf>>::new, |mut map, file| {
+            match (file.metadata(), partial_hash(file, args.algorithm)) {
+                (Ok(metadata), Ok(hash)) => {
+                    bytes_processed.fetch_add(metadata.len().min(PARTIAL_HASH_BLOCK_SIZE as u64), Ordering::Relaxed);
+                    map.entry((metadata.len(), hash)).or_insert(Vec::new()).push(file.clone());
+                },
+                (_, Err(error)) => eprintln!("{}", Red.paint(format!("Error: An error occurred while hashing {}: {}", file.display(), error))),
+                (Err(error), _) => eprintln!("{}", Red.paint(format!("Error: Could not stat {}: {}", file.display(), error)))
+            }
+            progress_bar.inc(1);
+            progress_bar.set_message(HumanBytes(bytes_processed.load(Ordering::Relaxed)).to_string());
+            map
+        })
+        .reduce(HashMap::new, |mut map1, map2| {
+            for (key, mut files) in map2 {
+                map1.entry(key).or_insert(Vec::new()).append(&mut files);
+            }
+            map1
+        });
+
+    // Stage 3: only files whose size AND first block collide (true candidates for being
+    // duplicates) get their full content hashed, in parallel, and are grouped by that full hash:
+    let mut needs_full_hash: Vec<PathBuf> = Vec::new();
+    for ((_size, partial_hash), files) in size_and_partial_hash_to_files {
+        if files.len() == 1 {
+            hash_to_files.entry(partial_hash).or_insert(Vec::new()).extend(files);
+        } else {
+            needs_full_hash.extend(files);
+        }
+    }
+    // These files were already counted once in stage 2 and are counted again below:
+    progress_bar.inc_length(needs_full_hash.len() as u64);
+    let full_hash_to_files: HashMap<String, Vec<PathBuf>> = needs_full_hash.par_iter()
+        .fold(HashMap::<String, Vec<PathBuf>>::new, |mut map, file| {
+            // Both a `--cache` hit and a freshly-computed `file_hash` are full-content hashes, so
+            // it's always safe to substitute one for the other here:
+            let key = cache_key(file, args.algorithm).ok();
+            let cached = key.clone().and_then(|key| cache.lock().unwrap().get(&key).cloned());
+            match cached.map(Ok).unwrap_or_else(|| file_hash(file, args.algorithm)) {
+                Ok(hash) => {
+                    if let Ok(metadata) = file.metadata() {
+                        bytes_processed.fetch_add(metadata.len(), Ordering::Relaxed);
+                    }
+                    if let Some(key) = key {
+                        cache.lock().unwrap().insert(key, hash.clone());
+                    }
+                    map.entry(hash).or_insert(Vec::new()).push(file.clone())
+                },
+                Err(error) => eprintln!("{}", Red.paint(format!("Error: An error occurred while hashing {}: {}", file.display(), error)))
+            }
+            progress_bar.inc(1);
+            progress_bar.set_message(HumanBytes(bytes_processed.load(Ordering::Relaxed)).to_string());
+            map
+        })
+        .reduce(HashMap::new, |mut map1, map2| {
+            for (hash, mut files) in map2 {
+                map1.entry(hash).or_insert(Vec::new()).append(&mut files);
+            }
+            map1
+        });
+    for (hash, files) in full_hash_to_files {
+        hash_to_files.entry(hash).or_insert(Vec::new()).extend(files);
+    }
+    progress_bar.finish_and_clear();
+
     // Turn the HashMap into a Vec to be able to sort it by hash:
     let mut hash_to_files: Vec<(String, Vec<PathBuf>)> = hash_to_files.into_iter().collect::<Vec<(String, Vec<PathBuf>)>>();
     hash_to_files.sort_unstable_by(|(hash1, _), (hash2, _)| hash1.cmp(&hash2)); // sort_unstable_by_key would require inefficient cloning!
@@ -90,49 +435,163 @@ fn main() {
         );
     }
 
-    // Do the actual print-out:
-    let mut counter = 1;
-    for (hash, files) in hash_to_files {
+    // Render the (sorted, possibly diffonly-filtered) result in whichever `--format` was requested:
+    match args.format {
+        OutputFormat::Table => render_table(&args, &hash_to_files),
+        OutputFormat::Json => render_json(&args, &hash_to_files),
+        OutputFormat::Csv => render_csv(&args, &hash_to_files),
+    }
+
+    // Write the (possibly updated) cache back out, if `--cache` was given:
+    if let Some(cache_path) = args.cache {
+        let cache = cache.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match bincode::serialize(&cache) {
+            Ok(bytes) => if let Err(error) = fs::write(&cache_path, bytes) {
+                eprintln!("{}", Red.paint(format!("Error: Could not write cache to {}: {}", cache_path.display(), error)))
+            },
+            Err(error) => eprintln!("{}", Red.paint(format!("Error: Could not serialize cache: {}", error)))
+        }
+    }
+}
+
+/// Takes a String `s` and makes it have a fixed length `len`.
+/// When `s` is longer than `len`, it is cut off.
+/// When `s` is shorter than `len`, the `padding` character is appended n times.
+fn fixed_length(s: &str, len: usize, padding: &str) -> String {
+    s.graphemes(true).chain(iter::repeat(padding)).take(len).collect::<String>()
+    // format!("{: <32}", s) is an alternative way of padding (but it does not cut it off when it's longer!)
+}
+
+/// Maps a directory to the files below it (directly or in a sub-folder) that have a given hash.
+fn files_in_dir<'a>(dir: &Path, files: &'a [PathBuf]) -> Vec<&'a PathBuf> {
+    files.iter().filter(|file| file.starts_with(dir)).collect()
+}
+
+/// Renders the result as the original fixed-width ASCII table.
+fn render_table(args: &Args, hash_to_files: &[(String, Vec<PathBuf>)]) {
+    println!(); // Put a newline over and under the ASCII table to make it more readable!
+    println!("#\t{}{}\t{}",
+             args.algorithm.name(),
+             " ".repeat(args.algorithm.hex_len().saturating_sub(args.algorithm.name().len())),
+             args.directories.iter().map(|dir: &PathBuf| fixed_length(dir.file_name().map(|os_str| os_str.to_str()).flatten().unwrap_or("???"), args.colwidth, " ")).collect::<Vec<String>>().join("\t")
+    );
+    for (counter, (hash, files)) in hash_to_files.iter().enumerate() {
         println!("{}\t{}\t{}",
-                 counter,
+                 counter + 1,
                  hash,
                  args.directories.iter()
-                     // Map each directory to all the files in it with the hash `hash` ("in it" meaning directly in it, i.e. not within sub-folders!):
-                     .map(|dir| files.iter().filter(|file| file.parent().unwrap() == dir).collect::<Vec<&PathBuf>>()) // file.starts_with(dir) would also allow the file to be in a subdir of dir!
-                     // Map 0 files to "–", map 1 file to its file name, map 2+ files to "(X files)":
-                     .map(|files| match files.len() {
+                     .map(|dir| (files_in_dir(dir, files), dir))
+                     // Map 0 files to "–", map 1 file to its path relative to `dir` (so that files with identical
+                     // content but in differently-nested subfolders can still be told apart), map 2+ files to "(X files)":
+                     .map(|(files, dir)| match files.len() {
                          0 => "–".to_string(),
-                         1 => files[0].file_name().map(|file_name| file_name.to_str()).flatten().unwrap_or("(1 file)").to_string(),
+                         1 => files[0].strip_prefix(dir).unwrap_or(files[0]).to_str().unwrap_or("(1 file)").to_string(),
                          n => format!("({} files)", n)
                      })
                      .map(|column_string: String| fixed_length(&column_string, args.colwidth, " "))
                      .collect::<Vec<String>>().join("\t")
         );
-        counter += 1;
     }
     println!();
 }
 
-/// Takes a String `s` and makes it have a fixed length `len`.
-/// When `s` is longer than `len`, it is cut off.
-/// When `s` is shorter than `len`, the `padding` character is appended n times.
-fn fixed_length(s: &str, len: usize, padding: &str) -> String {
-    s.graphemes(true).chain(iter::repeat(padding)).take(len).collect::<String>()
-    // format!("{: <32}", s) is an alternative way of padding (but it does not cut it off when it's longer!)
+/// One entry of a `JsonRecord`'s `files` array: how many (and, if exactly one, which) of the
+/// files with a given hash live below a given directory.
+#[derive(Serialize)]
+struct JsonFileEntry {
+    directory: String,
+    path: Option<String>,
+    count: usize,
+}
+
+/// One row of JSON output: a hash, the algorithm it was computed with, and where it was found.
+#[derive(Serialize)]
+struct JsonRecord {
+    hash: String,
+    algorithm: String,
+    files: Vec<JsonFileEntry>,
 }
 
-/// Hashes the content of a given file (computes the digest).
-fn file_hash<P: AsRef<Path>>(file_path: P) -> Result<String, Error> {
-    // cf. https://rust-lang-nursery.github.io/rust-cookbook/cryptography/hashing.html
+/// Renders the result as a JSON array of records, one per hash.
+fn render_json(args: &Args, hash_to_files: &[(String, Vec<PathBuf>)]) {
+    let records: Vec<JsonRecord> = hash_to_files.iter().map(|(hash, files)| JsonRecord {
+        hash: hash.clone(),
+        algorithm: args.algorithm.name().to_string(),
+        files: args.directories.iter().map(|dir| {
+            let matching = files_in_dir(dir, files);
+            JsonFileEntry {
+                directory: dir.display().to_string(),
+                path: match matching.len() {
+                    1 => matching[0].strip_prefix(dir).ok().map(|path| path.display().to_string()),
+                    _ => None,
+                },
+                count: matching.len(),
+            }
+        }).collect(),
+    }).collect();
+    match serde_json::to_string_pretty(&records) {
+        Ok(json) => println!("{}", json),
+        Err(error) => eprintln!("{}", Red.paint(format!("Error: Could not serialize result to JSON: {}", error)))
+    }
+}
+
+/// Renders the result as CSV, with one row per hash and one column per directory.
+fn render_csv(args: &Args, hash_to_files: &[(String, Vec<PathBuf>)]) {
+    let mut writer = csv::Writer::from_writer(std::io::stdout());
+    let mut header = vec!["#".to_string(), args.algorithm.name().to_string()];
+    header.extend(args.directories.iter().map(|dir| dir.display().to_string()));
+    if let Err(error) = writer.write_record(&header) {
+        eprintln!("{}", Red.paint(format!("Error: Could not write CSV header: {}", error)));
+        return;
+    }
+    for (counter, (hash, files)) in hash_to_files.iter().enumerate() {
+        let mut record = vec![(counter + 1).to_string(), hash.clone()];
+        for dir in args.directories.iter() {
+            let matching = files_in_dir(dir, files);
+            record.push(match matching.len() {
+                0 => "".to_string(),
+                1 => matching[0].strip_prefix(dir).unwrap_or(matching[0]).display().to_string(),
+                n => format!("({} files)", n)
+            });
+        }
+        if let Err(error) = writer.write_record(&record) {
+            eprintln!("{}", Red.paint(format!("Error: Could not write CSV row: {}", error)));
+            return;
+        }
+    }
+    if let Err(error) = writer.flush() {
+        eprintln!("{}", Red.paint(format!("Error: Could not flush CSV output: {}", error)));
+    }
+}
+
+/// How many bytes of a file are read to compute its `partial_hash`.
+const PARTIAL_HASH_BLOCK_SIZE: usize = 4096;
+
+/// Hashes only the first `PARTIAL_HASH_BLOCK_SIZE` bytes of a given file with the given
+/// `algorithm`. Used as a cheap first pass to group files before falling back to a full-content
+/// `file_hash`.
+fn partial_hash<P: AsRef<Path>>(file_path: P, algorithm: HashAlgorithm) -> Result<String, Error> {
     let file: File = File::open(file_path)?;
-    let reader: BufReader<File> = BufReader::new(file);
-    let digest: Digest = sha256_digest(reader)?;
-    Ok(HEXUPPER.encode(digest.as_ref()))
+    let mut reader: BufReader<File> = BufReader::new(file);
+    let mut buffer = [0; PARTIAL_HASH_BLOCK_SIZE];
+    let mut hasher = algorithm.hasher();
+    let mut remaining = PARTIAL_HASH_BLOCK_SIZE;
+    while remaining > 0 {
+        let count = reader.read(&mut buffer[..remaining])?;
+        if count == 0 {
+            break; // file is shorter than PARTIAL_HASH_BLOCK_SIZE
+        }
+        hasher.update(&buffer[..count]);
+        remaining -= count;
+    }
+    Ok(hasher.finish_hex())
 }
 
-/// Copied from https://rust-lang-nursery.github.io/rust-cookbook/cryptography/hashing.html
-fn sha256_digest<R: Read>(mut reader: R) -> Result<Digest, Error> {
-    let mut context = Context::new(&SHA256);
+/// Hashes the full content of a given file with the given `algorithm`.
+fn file_hash<P: AsRef<Path>>(file_path: P, algorithm: HashAlgorithm) -> Result<String, Error> {
+    let file: File = File::open(file_path)?;
+    let mut reader: BufReader<File> = BufReader::new(file);
+    let mut hasher = algorithm.hasher();
     let mut buffer = [0; 1024];
 
     loop {
@@ -140,8 +599,8 @@ fn sha256_digest<R: Read>(mut reader: R) -> Result<Digest, Error> {
         if count == 0 {
             break;
         }
-        context.update(&buffer[..count]);
+        hasher.update(&buffer[..count]);
     }
 
-    Ok(context.finish())
+    Ok(hasher.finish_hex())
 }
\ No newline at end of file
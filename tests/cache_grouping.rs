@@ -0,0 +1,56 @@
+use std::fs;
+use std::process::Command;
+
+/// Regression test for the `--cache` pipeline (see the chunk0-5 fix commits in git history): a
+/// cache hit must not be pulled out of the candidate pool before the size/partial-hash
+/// uniqueness checks run, or a cached file and a newly-added duplicate of it can end up keyed
+/// differently and wrongly reported as distinct.
+///
+/// Scenario: hash+cache `a.txt` (a temporary duplicate makes it get a full-content hash, which is
+/// the only kind that is cached), then in a second run, with the duplicate gone, add a
+/// byte-identical `b.txt` (same size, not yet cached) and an unrelated `c.txt` of the same size.
+/// `a.txt` must still group with `b.txt`, and not with `c.txt`.
+#[test]
+fn cache_hit_does_not_escape_size_uniqueness_check() {
+    let root = std::env::temp_dir().join(format!("compare_folders_cache_test_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    let dir = root.join("tree");
+    fs::create_dir_all(&dir).unwrap();
+    // Kept outside `dir` so it isn't itself picked up as a candidate file:
+    let cache_file = root.join("cache.bin");
+    let bin = env!("CARGO_BIN_EXE_compare_folders");
+
+    fs::write(dir.join("a.txt"), b"SAME-CONTENT-XYZ").unwrap();
+    // Without a same-size duplicate, `a.txt` would only get a partial hash and not be cached:
+    fs::write(dir.join("a_copy.txt"), b"SAME-CONTENT-XYZ").unwrap();
+    let status = Command::new(bin)
+        .args(["--cache", cache_file.to_str().unwrap(), dir.to_str().unwrap()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    fs::remove_file(dir.join("a_copy.txt")).unwrap();
+
+    // `b.txt` is a byte-identical duplicate of the now-cached `a.txt`; `c.txt` is the same size
+    // but different content, so it must stay in its own group:
+    fs::write(dir.join("b.txt"), b"SAME-CONTENT-XYZ").unwrap();
+    fs::write(dir.join("c.txt"), b"OTHER-CONTENT-AB").unwrap();
+
+    let output = Command::new(bin)
+        .args(["--cache", cache_file.to_str().unwrap(), "--format", "json", dir.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let records: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let counts: Vec<u64> = records.as_array().unwrap().iter()
+        .map(|record| record["files"][0]["count"].as_u64().unwrap())
+        .collect();
+
+    // One group of 2 (a.txt + b.txt) and one group of 1 (c.txt) – if the cache hit for a.txt
+    // escaped the uniqueness check, this would instead be three groups of 1:
+    let mut sorted_counts = counts.clone();
+    sorted_counts.sort_unstable();
+    assert_eq!(sorted_counts, vec![1, 2], "expected a.txt+b.txt grouped together and c.txt alone, got counts {:?}", counts);
+
+    fs::remove_dir_all(&root).ok();
+}